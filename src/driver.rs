@@ -0,0 +1,183 @@
+//! Wraps [`crate::scheduler::State`] as something that can actually wake a
+//! long-running process when a ping is due, rather than a lazy iterator a
+//! caller has to poll by hand. [`Driver`] is the async (`futures::Stream`)
+//! flavor; [`BlockingDriver`] is the synchronous equivalent for code that
+//! isn't running inside an executor.
+//!
+//! Both recompute the next ping against the real current time whenever
+//! they're polled, so a process that was suspended (or just slow to poll)
+//! catches up by fast-forwarding the underlying LCG via [`State::next_time`]'s
+//! `pow` jump instead of firing every ping that was missed in a burst.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use tokio::time::Sleep;
+
+use crate::scheduler::State;
+
+fn sleep_duration(target: DateTime<Utc>, now: DateTime<Utc>) -> std::time::Duration {
+    (target - now).to_std().unwrap_or(std::time::Duration::ZERO)
+}
+
+/// An asynchronous ping driver built on top of [`State`].
+///
+/// Each poll sleeps until the next scheduled ping and then yields it.
+pub struct Driver {
+    state: State,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Driver {
+    pub fn new(state: State) -> Driver {
+        Driver { state, sleep: None }
+    }
+
+    /// The `State` this driver is walking.
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Mutable access to the wrapped `State`, e.g. to call `set_gap` before
+    /// [`Driver::reset`]ting the driver to retarget against it.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Drop any in-flight sleep so the next poll retargets against the
+    /// current `State`, e.g. after its `gap` has been changed mid-run.
+    pub fn reset(&mut self) {
+        if self.sleep.take().is_some() {
+            // `state` was already advanced to the abandoned target; rewind it
+            // to before now so the next poll's `next_time` recomputes.
+            self.state.prev_time(Utc::now());
+        }
+    }
+}
+
+impl Stream for Driver {
+    type Item = DateTime<Utc>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.sleep.is_none() {
+            let now = Utc::now();
+            self.state.next_time(now);
+            let duration = sleep_duration(self.state.time(), now);
+            self.sleep = Some(Box::pin(tokio::time::sleep(duration)));
+        }
+
+        let sleep = self.sleep.as_mut().unwrap();
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                let ping = self.state.time();
+                self.sleep = None;
+                Poll::Ready(Some(ping))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The blocking counterpart to [`Driver`], for callers that aren't running
+/// inside an async executor. Iterating it parks the current thread until
+/// the next ping is due.
+pub struct BlockingDriver {
+    state: State,
+}
+
+impl BlockingDriver {
+    pub fn new(state: State) -> BlockingDriver {
+        BlockingDriver { state }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Mutable access to the wrapped `State`, e.g. to call `set_gap` mid-run.
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+}
+
+impl Iterator for BlockingDriver {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let now = Utc::now();
+        self.state.next_time(now);
+
+        let duration = sleep_duration(self.state.time(), now);
+        std::thread::sleep(duration);
+
+        Some(self.state.time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockingDriver, Driver};
+    use crate::scheduler::State;
+    use chrono::Utc;
+    use futures::{FutureExt, StreamExt};
+    use rug::Integer;
+
+    /// `tokio::time::pause` only virtualizes `tokio::time`'s notion of "now";
+    /// `Driver` computes its target against the real `Utc::now()`, so under a
+    /// paused clock a pending sleep just auto-advances instantly once nothing
+    /// else is runnable instead of burning real wall-clock time.
+    #[tokio::test(start_paused = true)]
+    async fn test_driver_yields_the_ping_it_computed() {
+        let mut driver = Driver::new(State::default());
+
+        let ping = driver.next().await.unwrap();
+
+        assert_eq!(ping, driver.state().time());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_driver_catches_up_instead_of_bursting() {
+        // a `State` anchored decades in the past, as if the process had been
+        // suspended since then: the driver must target the next ping after
+        // the real current time, not replay every ping missed in between.
+        let mut driver = Driver::new(State::from_millis(0));
+
+        let caught_up = driver.next().await.unwrap();
+
+        assert!(Utc::now().signed_duration_since(caught_up).num_days() < 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reset_after_set_gap_retargets_a_pending_sleep() {
+        let mut driver = Driver::new(State::default());
+
+        // poll once to commit to a target under the original ~45 minute
+        // gap, without letting the (paused) clock advance to it.
+        assert!(driver.next().now_or_never().is_none());
+        let original_target = driver.state().time();
+
+        // retarget to a much shorter gap and drop the in-flight sleep so
+        // the next poll recomputes against it instead of replaying the
+        // stale target.
+        driver.state_mut().set_gap(Integer::from(1));
+        driver.reset();
+
+        let retargeted = driver.next().await.unwrap();
+
+        assert_ne!(retargeted, original_target);
+    }
+
+    #[test]
+    fn test_blocking_driver_yields_the_ping_it_computed() {
+        let mut state = State::from_millis(Utc::now().timestamp_millis());
+        state.set_gap(Integer::from(1));
+
+        let mut driver = BlockingDriver::new(state);
+        let ping = driver.next().unwrap();
+
+        assert_eq!(ping, driver.state().time());
+    }
+}
@@ -0,0 +1,132 @@
+//! A bounded log of pings the user has actually been prompted for, so a
+//! long-running client can record answers (and check whether a given ping
+//! was already recorded) without the log growing without bound.
+
+use std::collections::{HashSet, VecDeque};
+
+use chrono::{DateTime, Utc};
+
+/// A single recorded ping: when it fired, and whatever the user answered
+/// about what they were doing (if anything yet).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub answer: Option<String>,
+}
+
+/// A FIFO log of pings, deduplicated by timestamp and prunable by age.
+///
+/// Entries are kept in insertion order in a deque, so the earliest-inserted
+/// entry is at the front and can be evicted in O(1); this is chronological
+/// order only when pings are pushed in the order they fired. A parallel
+/// hash set of the same timestamps makes "have I already
+/// logged this ping" an O(1) check instead of a scan.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    entries: VecDeque<Entry>,
+    seen: HashSet<DateTime<Utc>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Record a ping. Returns `false` without modifying the log if `time`
+    /// was already recorded, since the same ping increment must never be
+    /// logged twice.
+    pub fn push(&mut self, time: DateTime<Utc>, answer: Option<String>) -> bool {
+        if !self.seen.insert(time) {
+            return false;
+        }
+
+        self.entries.push_back(Entry { time, answer });
+        true
+    }
+
+    /// Evict entries from the front of the log while the front entry is
+    /// older than `before`. Stops at the first entry that is not, so an
+    /// older entry pushed after it is not evicted.
+    pub fn prune(&mut self, before: DateTime<Utc>) {
+        while matches!(self.entries.front(), Some(entry) if entry.time < before) {
+            let entry = self.entries.pop_front().unwrap();
+            self.seen.remove(&entry.time);
+        }
+    }
+
+    /// Whether a ping at `time` has already been recorded.
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.seen.contains(&time)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use chrono::{offset::TimeZone, Utc};
+
+    fn ts(millis: i64) -> chrono::DateTime<Utc> {
+        Utc.timestamp_millis(millis)
+    }
+
+    #[test]
+    fn test_push_and_contains() {
+        let mut h = History::new();
+
+        assert!(h.push(ts(100), None));
+        assert!(h.contains(ts(100)));
+        assert!(!h.contains(ts(200)));
+    }
+
+    #[test]
+    fn test_push_rejects_duplicate_timestamp() {
+        let mut h = History::new();
+
+        assert!(h.push(ts(100), Some("writing".to_string())));
+        assert!(!h.push(ts(100), Some("reading".to_string())));
+        assert_eq!(h.len(), 1);
+    }
+
+    #[test]
+    fn test_iter_is_insertion_order() {
+        let mut h = History::new();
+        h.push(ts(300), None);
+        h.push(ts(100), None);
+        h.push(ts(200), None);
+
+        assert_eq!(
+            h.iter().map(|e| e.time).collect::<Vec<_>>(),
+            vec![ts(300), ts(100), ts(200)]
+        );
+    }
+
+    #[test]
+    fn test_prune_evicts_oldest_and_forgets_them() {
+        let mut h = History::new();
+        h.push(ts(100), None);
+        h.push(ts(200), None);
+        h.push(ts(300), None);
+
+        h.prune(ts(250));
+
+        assert_eq!(h.len(), 1);
+        assert!(!h.contains(ts(100)));
+        assert!(!h.contains(ts(200)));
+        assert!(h.contains(ts(300)));
+
+        // a previously evicted timestamp can be logged again.
+        assert!(h.push(ts(100), None));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod driver;
+pub mod history;
+pub mod scheduler;
@@ -6,8 +6,9 @@
 //!
 //! [the Beeminder forums]: https://forum.beeminder.com/t/possible-new-tagtime-universal-ping-algorithm/4143/31
 
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use rug::Integer;
+use serde::{Deserialize, Serialize};
 use std::iter::Iterator;
 
 const IA: i64 = 3125;
@@ -15,11 +16,30 @@ const IM: i64 = 34359738337;
 const GAP: i64 = 45 * 60;
 const SEED: i64 = 20180809;
 
+/// (De)serializes a `rug::Integer` as a decimal string, since `Integer`
+/// has no portable binary representation of its own across platforms.
+mod integer_str {
+    use rug::Integer;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Integer, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Integer, D::Error> {
+        let repr = String::deserialize(deserializer)?;
+        repr.parse().map_err(D::Error::custom)
+    }
+}
+
 /// A linear congruence generator, whose offset (increment) is 0.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LCG {
+    #[serde(with = "integer_str")]
     pub multiplier: Integer,
+    #[serde(with = "integer_str")]
     pub modulus: Integer,
+    #[serde(with = "integer_str")]
     pub state: Integer,
 }
 
@@ -36,6 +56,32 @@ impl LCG {
     pub fn next(&mut self) {
         self.pow(Integer::from(1))
     }
+
+    /// The modular inverse of `multiplier` mod `modulus`.
+    ///
+    /// `pow`/`next` walk the generator forward by raising `multiplier` to a
+    /// power; this is the same trick run in reverse, so `multiplier` and
+    /// `modulus` must be coprime for it to exist. With the default
+    /// parameters this always holds (`IA = 3125 = 5^5` is coprime to `IM`),
+    /// but a caller who swaps in their own `LCG` can violate it, in which
+    /// case this panics.
+    pub fn invert(&self) -> Integer {
+        self.multiplier
+            .clone()
+            .invert(&self.modulus)
+            .unwrap_or_else(|_| panic!("LCG multiplier must be coprime with modulus to invert"))
+    }
+
+    /// Step the generator backward by `exp` increments, the inverse of `pow`.
+    pub fn pow_back(&mut self, exp: Integer) {
+        let inv_multiplier = self.invert().pow_mod(&exp, &self.modulus).unwrap();
+        self.state = (inv_multiplier * &self.state) % &self.modulus;
+    }
+
+    /// Step the generator backward by one increment, the inverse of `next`.
+    pub fn prev(&mut self) {
+        self.pow_back(Integer::from(1))
+    }
 }
 
 impl Default for LCG {
@@ -48,17 +94,75 @@ impl Default for LCG {
     }
 }
 
-#[derive(Debug, Clone)]
+/// How finely ping times are quantized, expressed as the duration of a
+/// single tick in milliseconds. `State` hashes increments of wall-clock
+/// time rather than wall-clock time itself, and this controls how long an
+/// increment is; the default of 100ms (deciseconds) preserves tagtime's
+/// original granularity.
+///
+/// Stored as a tick duration rather than a ticks-per-second count so that
+/// resolutions coarser than one tick per second (e.g. a once-a-minute
+/// schedule, via `Resolution::from_millis_per_tick(60_000)`) are
+/// representable too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resolution {
+    millis_per_tick: i64,
+}
+
+impl Resolution {
+    /// A resolution with a tick every `millis_per_tick` milliseconds.
+    pub fn from_millis_per_tick(millis_per_tick: i64) -> Resolution {
+        Resolution { millis_per_tick }
+    }
+
+    /// A resolution of `ticks_per_second` ticks per second. For schedules
+    /// coarser than 1Hz (e.g. a ping every minute), use
+    /// [`Resolution::from_millis_per_tick`] instead.
+    ///
+    /// Panics if `ticks_per_second` doesn't divide evenly into 1000
+    /// milliseconds, since silently rounding would make the actual
+    /// resolution different from the one requested.
+    pub fn from_ticks_per_second(ticks_per_second: i64) -> Resolution {
+        assert_eq!(
+            1000 % ticks_per_second,
+            0,
+            "ticks_per_second ({}) must divide 1000 evenly; use Resolution::from_millis_per_tick \
+             for resolutions that don't",
+            ticks_per_second,
+        );
+        Resolution::from_millis_per_tick(1000 / ticks_per_second)
+    }
+
+    pub fn millis_per_tick(&self) -> i64 {
+        self.millis_per_tick
+    }
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Resolution::from_ticks_per_second(10)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct State {
     time: DateTime<Utc>,
     /// Desired average gap length in seconds
+    #[serde(with = "integer_str")]
     gap: Integer,
     lcg: LCG,
+    #[serde(default)]
+    resolution: Resolution,
 }
 
 impl State {
     pub fn new(time: DateTime<Utc>, gap: Integer, lcg: LCG) -> State {
-        State { time, gap, lcg }
+        State {
+            time,
+            gap,
+            lcg,
+            resolution: Resolution::default(),
+        }
     }
 
     pub fn from_millis(n: i64) -> State {
@@ -67,16 +171,57 @@ impl State {
         s
     }
 
+    /// Checkpoint this schedule to a compact JSON document (`time`, `gap`,
+    /// and the full `LCG` triple) so it can be [`State::load`]ed later and
+    /// produce exactly the same future ping sequence it would have without
+    /// the restart.
+    pub fn save(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Restore a schedule previously checkpointed with [`State::save`].
+    pub fn load(data: &str) -> serde_json::Result<State> {
+        serde_json::from_str(data)
+    }
+
     pub fn lcg(&self) -> &LCG {
         &self.lcg
     }
 
+    /// The most recently computed ping time.
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// Desired average gap length in seconds.
+    pub fn gap(&self) -> &Integer {
+        &self.gap
+    }
+
+    /// Retarget the schedule's desired average gap, e.g. so a future
+    /// [`State::next_time`] reflects a gap change made mid-run.
+    pub fn set_gap(&mut self, gap: Integer) {
+        self.gap = gap;
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// Reconfigure how finely ping times are quantized. Takes effect from
+    /// the next call to `next_time`/`prev_time` onward.
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+    }
+
     pub fn next_time(&mut self, cur: DateTime<Utc>) {
         if cur >= self.time {
-            let threshold = &self.lcg.modulus / (&self.gap * Integer::from(10));
+            let millis_per_tick = self.resolution.millis_per_tick();
+            let threshold = (&self.lcg.modulus * Integer::from(millis_per_tick))
+                / (&self.gap * Integer::from(1000));
 
-            let prev_incs = self.time.timestamp_millis() / 100;
-            let cur_incs = cur.timestamp_millis() / 100;
+            let prev_incs = self.time.timestamp_millis() / millis_per_tick;
+            let cur_incs = cur.timestamp_millis() / millis_per_tick;
             let mut new_incs = cur_incs + 1;
 
             if cur_incs > prev_incs {
@@ -91,9 +236,140 @@ impl State {
                 new_incs += 1;
             }
 
-            self.time = Utc.timestamp_millis(new_incs * 100);
+            self.time = Utc.timestamp_millis(new_incs * millis_per_tick);
+        }
+    }
+
+    /// The inverse of `next_time`: seeks backward to the last accepted ping
+    /// in an increment before `cur`'s own increment, reusing the LCG's modular inverse (see
+    /// [`LCG::invert`]) to jump to `cur`'s increment directly rather than
+    /// replaying every rejected increment from the beginning.
+    pub fn prev_time(&mut self, cur: DateTime<Utc>) {
+        if cur <= self.time {
+            let millis_per_tick = self.resolution.millis_per_tick();
+            let threshold = (&self.lcg.modulus * Integer::from(millis_per_tick))
+                / (&self.gap * Integer::from(1000));
+
+            let prev_incs = self.time.timestamp_millis() / millis_per_tick;
+            let cur_incs = cur.timestamp_millis() / millis_per_tick;
+            let mut new_incs = cur_incs - 1;
+
+            if prev_incs > cur_incs {
+                self.lcg.pow_back(Integer::from(prev_incs - cur_incs));
+            }
+
+            while {
+                self.lcg.prev();
+                &self.lcg.state
+            } >= &threshold
+            {
+                new_incs -= 1;
+            }
+
+            self.time = Utc.timestamp_millis(new_incs * millis_per_tick);
         }
     }
+
+    /// All pings in the half-open window `[start, end)`, without mutating
+    /// `self` (a clone does the walking), so overlapping windows can be
+    /// queried repeatedly from one configured schedule.
+    ///
+    /// Seeks directly to the first ping at or after `start` by jumping the
+    /// LCG to `start`'s increment via [`State::next_time`]'s `pow` jump,
+    /// then yields accepted pings until one reaches `end`.
+    pub fn pings_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> impl Iterator<Item = DateTime<Utc>> {
+        let mut seek = self.clone();
+
+        // `next_time` finds the first accepted increment *after* its
+        // argument, so back up by one increment to make `start` itself a
+        // candidate.
+        let seek_from = start - Duration::milliseconds(seek.resolution.millis_per_tick());
+
+        // The receiver's cursor may already be past `start` (e.g. it's
+        // sitting at "now" and the window reaches back to "yesterday
+        // noon"). `next_time` only ever moves forward, so rewind past
+        // `seek_from` first via `prev_time`'s modular-inverse jump; only
+        // then is it safe to seek forward to the start of the window.
+        if seek_from < seek.time {
+            seek.prev_time(seek_from);
+        }
+        if seek_from >= seek.time {
+            seek.next_time(seek_from);
+        }
+
+        // If `start` falls inside a tick, the ping found may be that tick's,
+        // which is earlier than `start`; skip it.
+        let mut next_candidate = Some(seek.time()).filter(|t| *t >= start);
+
+        std::iter::from_fn(move || {
+            let candidate = next_candidate.take().unwrap_or_else(|| seek.next().unwrap());
+
+            if candidate < end {
+                Some(candidate)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Walk the shared LCG sequence once, densely sampled at the smallest
+    /// of `gaps`, tagging each accepted ping with every gap (from `gaps`)
+    /// whose schedule it also belongs to.
+    ///
+    /// This is the superset guarantee described in the module docs made
+    /// concrete: the ping times for a larger average gap are a strict
+    /// subset of those for a smaller one, so thresholds are nested and an
+    /// instant's membership can be read off with one comparison per gap,
+    /// stopping at the first gap it fails.
+    pub fn layered(&self, gaps: Vec<Integer>) -> impl Iterator<Item = LayeredPing> {
+        let mut sorted_gaps = gaps;
+        sorted_gaps.sort();
+
+        let mut walker = self.clone();
+        if let Some(finest) = sorted_gaps.first() {
+            walker.gap = finest.clone();
+        }
+
+        let millis_per_tick = Integer::from(walker.resolution.millis_per_tick());
+        let thresholds: Vec<Integer> = sorted_gaps
+            .iter()
+            .map(|gap| {
+                let numer: Integer = (&walker.lcg.modulus * &millis_per_tick).into();
+                let denom = gap * Integer::from(1000);
+                numer / denom
+            })
+            .collect();
+
+        std::iter::from_fn(move || {
+            walker.next_time(walker.time);
+            let time = walker.time;
+            let state = walker.lcg.state.clone();
+
+            let mut gaps = Vec::new();
+            for (gap, threshold) in sorted_gaps.iter().zip(thresholds.iter()) {
+                if state < *threshold {
+                    gaps.push(gap.clone());
+                } else {
+                    break;
+                }
+            }
+
+            Some(LayeredPing { time, gaps })
+        })
+    }
+}
+
+/// A single ping from a [`State::layered`] schedule: the instant it
+/// occurred, and every gap (from the list passed to `layered`) whose
+/// schedule it belongs to, finest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayeredPing {
+    pub time: DateTime<Utc>,
+    pub gaps: Vec<Integer>,
 }
 
 impl Iterator for State {
@@ -105,19 +381,27 @@ impl Iterator for State {
     }
 }
 
+impl DoubleEndedIterator for State {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.prev_time(self.time);
+        Some(self.time)
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         State {
             time: Utc::now(),
             gap: Integer::from(GAP),
             lcg: LCG::default(),
+            resolution: Resolution::default(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{State, LCG};
+    use super::{Resolution, State, LCG};
     use chrono::{offset::TimeZone, Utc};
     use rug::Integer;
 
@@ -147,6 +431,7 @@ mod tests {
             time: Utc.timestamp_millis(INIT_TIME),
             gap: Integer::from(GAP),
             lcg,
+            resolution: Resolution::default(),
         }
     }
 
@@ -192,4 +477,184 @@ mod tests {
             assert_eq!(lcg.state.to_i64().unwrap(), IDEAL_RNG[i]);
         }
     }
+
+    #[test]
+    fn test_rng_pow_back_undoes_pow() {
+        let lcg = create_lcg();
+
+        for i in 1..5 {
+            let mut fwd = lcg.clone();
+            fwd.pow(Integer::from(i));
+            fwd.pow_back(Integer::from(i));
+            assert_eq!(fwd.state, lcg.state);
+        }
+    }
+
+    #[test]
+    fn test_rng_prev_undoes_next() {
+        let mut lcg = create_lcg();
+
+        for _ in 0..4 {
+            let before = lcg.state.clone();
+            lcg.next();
+            lcg.prev();
+            assert_eq!(lcg.state, before);
+            lcg.next();
+        }
+    }
+
+    #[test]
+    fn test_prev_time() {
+        let mut s = create_state();
+
+        assert_eq!(
+            (&mut s)
+                .take(4)
+                .map(|x| x.timestamp_millis() / 100)
+                .collect::<Vec<_>>(),
+            IDEAL.to_vec()
+        );
+
+        for i in (0..3).rev() {
+            s.prev_time(s.time());
+            assert_eq!(s.time().timestamp_millis() / 100, IDEAL[i]);
+        }
+    }
+
+    #[test]
+    fn test_save_load_roundtrip() {
+        let mut s = create_state();
+        s.next_time(s.time());
+
+        let saved = s.save().unwrap();
+        let mut restored = State::load(&saved).unwrap();
+
+        assert_eq!(restored.time(), s.time());
+        assert_eq!(restored.gap(), s.gap());
+        assert_eq!(restored.lcg().state, s.lcg().state);
+
+        // the restored state should continue producing the same sequence.
+        assert_eq!(restored.next().unwrap(), s.next().unwrap());
+    }
+
+    #[test]
+    fn test_pings_between() {
+        let s = create_state();
+
+        let start = Utc.timestamp_millis(IDEAL[1] * 100);
+        let end = Utc.timestamp_millis(IDEAL[3] * 100);
+
+        assert_eq!(
+            s.pings_between(start, end)
+                .map(|x| x.timestamp_millis() / 100)
+                .collect::<Vec<_>>(),
+            IDEAL[1..3].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_pings_between_does_not_mutate() {
+        let s = create_state();
+
+        let start = Utc.timestamp_millis(IDEAL[0] * 100);
+        let end = Utc.timestamp_millis(IDEAL[2] * 100);
+
+        let _ = s.pings_between(start, end).collect::<Vec<_>>();
+        let _ = s.pings_between(start, end).collect::<Vec<_>>();
+
+        assert_eq!(s.time.timestamp_millis(), INIT_TIME);
+    }
+
+    #[test]
+    fn test_pings_between_seeks_backward_when_cursor_is_ahead() {
+        let mut s = create_state();
+        // advance the cursor past `start`, e.g. as if it were sitting at
+        // "now" while the query window reaches back into the past.
+        s.nth(1);
+        assert_eq!(s.time().timestamp_millis() / 100, IDEAL[1]);
+
+        let start = Utc.timestamp_millis(IDEAL[0] * 100);
+        let end = Utc.timestamp_millis(IDEAL[3] * 100);
+
+        assert_eq!(
+            s.pings_between(start, end)
+                .map(|x| x.timestamp_millis() / 100)
+                .collect::<Vec<_>>(),
+            IDEAL[0..3].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_default_resolution_preserves_decisecond_behavior() {
+        assert_eq!(Resolution::default().millis_per_tick(), 100);
+        assert_eq!(create_state().resolution(), Resolution::default());
+    }
+
+    #[test]
+    fn test_resolution_rescales_tick_granularity() {
+        let mut s = create_state();
+        s.set_resolution(Resolution::from_ticks_per_second(20));
+
+        assert_eq!(s.resolution().millis_per_tick(), 50);
+
+        // every yielded ping should land on the configured 50ms tick
+        // boundary, not the default 100ms one.
+        for t in (&mut s).take(4) {
+            assert_eq!(t.timestamp_millis() % 50, 0);
+        }
+    }
+
+    #[test]
+    fn test_resolution_supports_coarser_than_one_hertz() {
+        let mut s = create_state();
+        // a once-a-minute tick isn't representable as a whole number of
+        // ticks per second, so it needs the duration-based constructor.
+        s.set_resolution(Resolution::from_millis_per_tick(60_000));
+
+        assert_eq!(s.resolution().millis_per_tick(), 60_000);
+
+        for t in (&mut s).take(4) {
+            assert_eq!(t.timestamp_millis() % 60_000, 0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must divide 1000 evenly")]
+    fn test_from_ticks_per_second_rejects_non_divisors() {
+        Resolution::from_ticks_per_second(3);
+    }
+
+    #[test]
+    fn test_layered_respects_superset_guarantee() {
+        let fine_gap = Integer::from(GAP);
+        let coarse_gap = Integer::from(GAP * 3);
+
+        let mut fine_state = create_state();
+        let fine_times: Vec<i64> = (&mut fine_state)
+            .take(6)
+            .map(|t| t.timestamp_millis())
+            .collect();
+
+        let mut coarse_state = create_state();
+        coarse_state.set_gap(coarse_gap.clone());
+        let coarse_times: std::collections::HashSet<i64> = (&mut coarse_state)
+            .take(6)
+            .map(|t| t.timestamp_millis())
+            .collect();
+
+        let s = create_state();
+        let layered: Vec<_> = s
+            .layered(vec![fine_gap.clone(), coarse_gap.clone()])
+            .take(6)
+            .collect();
+
+        for (ping, expected_time) in layered.iter().zip(fine_times.iter()) {
+            assert_eq!(ping.time.timestamp_millis(), *expected_time);
+            assert!(ping.gaps.contains(&fine_gap));
+            assert_eq!(
+                ping.gaps.contains(&coarse_gap),
+                coarse_times.contains(expected_time)
+            );
+        }
+    }
 }